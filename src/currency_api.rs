@@ -1,14 +1,26 @@
 use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use anyhow::anyhow;
+use async_trait::async_trait;
 use reqwest::Client;
+use rust_decimal::Decimal;
 use serde::Deserialize;
+use tokio::task::JoinSet;
+
+use crate::crypto::CryptoProvider;
+use crate::error::ConverterError;
 
 #[derive(Clone, Debug)]
 pub(crate) struct CurrencyInfo {
     pub timestamp: Instant,
-    pub currency_rates: HashMap<String, f64>,
+    pub currency_rates: HashMap<String, Decimal>,
+    /// `Some(unix_secs)` the rates were persisted at if these came from a
+    /// [`LatestRate`](crate::rate_cache::LatestRate) fallback snapshot rather
+    /// than a live provider fetch.
+    pub fallback_persisted_at_unix_secs: Option<u64>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -17,75 +29,277 @@ struct CurrencyApiResponse {
     currency_rates: HashMap<String, f64>,
 }
 
-#[derive(Clone, Debug)]
-pub(crate) struct CurrencyApiClient {
-    ff_api_token: String,
-    xe_api_token: String,
-    api_rest_client: Client,
+/// Strategy used to merge the rates returned for a single currency code when
+/// more than one provider reports a value for it.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) enum Aggregation {
+    /// Use the rate from the first provider (in registration order) that returned it.
+    #[default]
+    FirstAvailable,
+    /// Use the arithmetic mean of all rates returned for the currency.
+    Average,
+    /// Use the median of all rates returned for the currency.
+    Median,
 }
 
-impl CurrencyApiClient {
-    pub fn new(ff_api_token: String, xe_api_token: String) -> Self {
-        let api_rest_client = Client::builder()
-            .https_only(true)
-            .timeout(Duration::from_secs(15))
-            .connect_timeout(Duration::from_secs(15))
-            .build()
-            .expect("Unable to build rest api client");
-        CurrencyApiClient {
-            ff_api_token,
-            xe_api_token,
-            api_rest_client,
+impl FromStr for Aggregation {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "first-available" => Ok(Aggregation::FirstAvailable),
+            "average" => Ok(Aggregation::Average),
+            "median" => Ok(Aggregation::Median),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Aggregation {
+    fn merge(self, mut rates: Vec<Decimal>) -> Decimal {
+        match self {
+            Aggregation::FirstAvailable => rates[0],
+            Aggregation::Average => {
+                rates.iter().sum::<Decimal>() / Decimal::from(rates.len() as u64)
+            }
+            Aggregation::Median => {
+                rates.sort();
+                let mid = rates.len() / 2;
+                if rates.len() % 2 == 0 {
+                    (rates[mid - 1] + rates[mid]) / Decimal::TWO
+                } else {
+                    rates[mid]
+                }
+            }
         }
     }
+}
 
-    pub async fn fetch_currencies(&self) -> anyhow::Result<CurrencyInfo> {
-        let fastforex_info = self.fetch_fastforex_info().await?;
-        let xe_info = self.fetch_xe_rates().await?;
+/// A single source of currency exchange rates, expressed against USD.
+#[async_trait]
+pub(crate) trait RateProvider: Send + Sync {
+    /// Human-readable identifier used in error messages and logs.
+    fn name(&self) -> &'static str;
 
-        let mut result = fastforex_info.currency_rates;
-        for (currency, rate) in xe_info.currency_rates.into_iter() {
-            result.entry(currency).or_insert(rate);
+    async fn fetch_rates(&self) -> anyhow::Result<HashMap<String, Decimal>>;
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct FastForexProvider {
+    api_token: String,
+    api_rest_client: Client,
+}
+
+impl FastForexProvider {
+    pub fn new(api_token: String, api_rest_client: Client) -> Self {
+        Self {
+            api_token,
+            api_rest_client,
         }
+    }
+}
 
-        Ok(CurrencyInfo {
-            timestamp: Instant::now(),
-            currency_rates: result,
-        })
+#[async_trait]
+impl RateProvider for FastForexProvider {
+    fn name(&self) -> &'static str {
+        "FastForex"
     }
 
-    async fn fetch_fastforex_info(&self) -> anyhow::Result<CurrencyApiResponse> {
+    async fn fetch_rates(&self) -> anyhow::Result<HashMap<String, Decimal>> {
         let request_url = format!(
             "https://api.fastforex.io/fetch-all?from=USD&api_key={}",
-            self.ff_api_token
+            self.api_token
         );
 
-        match self.api_rest_client.get(request_url).send().await {
-            Ok(response) => response
-                .json::<CurrencyApiResponse>()
-                .await
-                .map_err(Into::into),
-            Err(_) => Err(anyhow!("Unable to fetch currency info from FastForex")),
+        let response = match self.api_rest_client.get(request_url).send().await {
+            Ok(response) => response,
+            Err(_) => return Err(anyhow!("Unable to fetch currency info from FastForex")),
+        };
+
+        let parsed = response.json::<CurrencyApiResponse>().await?;
+        Ok(rates_to_decimal(parsed.currency_rates))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct XeProvider {
+    api_token: String,
+    api_rest_client: Client,
+}
+
+impl XeProvider {
+    pub fn new(api_token: String, api_rest_client: Client) -> Self {
+        Self {
+            api_token,
+            api_rest_client,
         }
     }
+}
 
-    async fn fetch_xe_rates(&self) -> anyhow::Result<CurrencyApiResponse> {
+#[async_trait]
+impl RateProvider for XeProvider {
+    fn name(&self) -> &'static str {
+        "XE"
+    }
+
+    async fn fetch_rates(&self) -> anyhow::Result<HashMap<String, Decimal>> {
         let request_result = self
             .api_rest_client
             .get("https://www.xe.com/api/protected/midmarket-converter/")
             .header(
                 reqwest::header::AUTHORIZATION,
-                format!("Basic {}", &self.xe_api_token),
+                format!("Basic {}", &self.api_token),
             )
             .send()
             .await;
 
-        match request_result {
-            Ok(response) => response
-                .json::<CurrencyApiResponse>()
-                .await
-                .map_err(Into::into),
-            Err(_) => Err(anyhow!("Unable to fetch currency info from XE")),
+        let response = match request_result {
+            Ok(response) => response,
+            Err(_) => return Err(anyhow!("Unable to fetch currency info from XE")),
+        };
+
+        let parsed = response.json::<CurrencyApiResponse>().await?;
+        Ok(rates_to_decimal(parsed.currency_rates))
+    }
+}
+
+// the fiat providers hand back plain JSON floats, so this is the one place
+// where a lossy f64 -> Decimal conversion happens; rates that don't fit a
+// Decimal (NaN, infinite) are dropped rather than failing the whole fetch
+fn rates_to_decimal(rates: HashMap<String, f64>) -> HashMap<String, Decimal> {
+    rates
+        .into_iter()
+        .filter_map(|(currency, rate)| Decimal::from_f64_retain(rate).map(|rate| (currency, rate)))
+        .collect()
+}
+
+#[derive(Clone)]
+pub(crate) struct CurrencyApiClient {
+    providers: Arc<Vec<Box<dyn RateProvider>>>,
+    aggregation: Aggregation,
+}
+
+impl CurrencyApiClient {
+    pub fn new(ff_api_token: String, xe_api_token: String, aggregation: Aggregation) -> Self {
+        let api_rest_client = Client::builder()
+            .https_only(true)
+            .timeout(Duration::from_secs(15))
+            .connect_timeout(Duration::from_secs(15))
+            .build()
+            .expect("Unable to build rest api client");
+
+        let providers: Vec<Box<dyn RateProvider>> = vec![
+            Box::new(FastForexProvider::new(
+                ff_api_token,
+                api_rest_client.clone(),
+            )),
+            Box::new(XeProvider::new(xe_api_token, api_rest_client.clone())),
+            Box::new(CryptoProvider::new(api_rest_client)),
+        ];
+
+        Self::with_providers(providers, aggregation)
+    }
+
+    pub(crate) fn with_providers(
+        providers: Vec<Box<dyn RateProvider>>,
+        aggregation: Aggregation,
+    ) -> Self {
+        Self {
+            providers: Arc::new(providers),
+            aggregation,
+        }
+    }
+
+    pub async fn fetch_currencies(&self) -> Result<CurrencyInfo, ConverterError> {
+        // fetch every provider concurrently rather than one after another, so
+        // a cache refresh costs the slowest provider's timeout rather than
+        // the sum of all of them
+        let mut fetches = JoinSet::new();
+        for index in 0..self.providers.len() {
+            let providers = self.providers.clone();
+            fetches.spawn(async move {
+                let provider = &providers[index];
+                (provider.name(), provider.fetch_rates().await)
+            });
+        }
+
+        let mut collected: HashMap<String, Vec<Decimal>> = HashMap::new();
+        let mut last_failed_provider = None;
+
+        while let Some(result) = fetches.join_next().await {
+            match result {
+                Ok((_, Ok(rates))) => {
+                    for (currency, rate) in rates {
+                        collected.entry(currency).or_default().push(rate);
+                    }
+                }
+                // a single dead provider shouldn't fail the whole fetch, as long
+                // as at least one other provider comes back with rates
+                Ok((name, Err(_))) => last_failed_provider = Some(name),
+                Err(_) => last_failed_provider = Some("a provider that panicked"),
+            }
+        }
+
+        if collected.is_empty() {
+            return Err(ConverterError::UpstreamUnavailable {
+                provider: last_failed_provider.unwrap_or("all providers").to_string(),
+            });
         }
+
+        let currency_rates = collected
+            .into_iter()
+            .map(|(currency, rates)| {
+                let merged_rate = self.aggregation.merge(rates);
+                (currency, merged_rate)
+            })
+            .collect();
+
+        Ok(CurrencyInfo {
+            timestamp: Instant::now(),
+            currency_rates,
+            fallback_persisted_at_unix_secs: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimals(values: &[&str]) -> Vec<Decimal> {
+        values
+            .iter()
+            .map(|value| Decimal::from_str_exact(value).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn first_available_uses_first_rate() {
+        let rates = decimals(&["1.1", "1.2", "1.3"]);
+        assert_eq!(
+            Aggregation::FirstAvailable.merge(rates),
+            Decimal::from_str_exact("1.1").unwrap()
+        );
+    }
+
+    #[test]
+    fn average_computes_arithmetic_mean() {
+        let rates = decimals(&["1", "2", "3"]);
+        assert_eq!(Aggregation::Average.merge(rates), Decimal::from(2));
+    }
+
+    #[test]
+    fn median_of_odd_length_is_the_middle_value() {
+        let rates = decimals(&["3", "1", "2"]);
+        assert_eq!(Aggregation::Median.merge(rates), Decimal::from(2));
+    }
+
+    #[test]
+    fn median_of_even_length_averages_the_two_middle_values() {
+        let rates = decimals(&["1", "4", "2", "3"]);
+        assert_eq!(
+            Aggregation::Median.merge(rates),
+            Decimal::from_str_exact("2.5").unwrap()
+        );
     }
 }
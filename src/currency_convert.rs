@@ -1,57 +1,76 @@
 use std::collections::HashMap;
-use std::fs;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use anyhow::anyhow;
 use itertools::Itertools;
-use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
+use rust_decimal::Decimal;
+use rusty_money::iso;
+use serde::Serialize;
+use tokio::sync::{broadcast, RwLock};
 
+use crate::crypto::find_crypto_currency;
 use crate::currency_api::{CurrencyApiClient, CurrencyInfo};
+use crate::error::ConverterError;
+use crate::rate_cache::{duration_since_unix_secs, LatestRate};
 
 const MAX_CACHE_TIME_SECS: u64 = 5 * 60;
-const ONE_USD_RATE: Option<f64> = Some(1.0f64);
+const ONE_USD_RATE: Option<Decimal> = Some(Decimal::ONE);
 
-#[derive(Deserialize, Debug)]
-struct SupportedCurrencies {
-    #[serde(alias = "currencies")]
-    currency_names: HashMap<String, String>,
-}
+// number of rate-update notifications that may be buffered for a slow
+// subscriber before it starts missing ticks (it will simply catch up on
+// the next broadcast rather than being disconnected)
+const RATE_UPDATE_CHANNEL_CAPACITY: usize = 16;
 
 #[derive(Serialize, Clone, Debug)]
 pub(crate) struct CurrencyConvertResult {
     pub base_currency: String,
     pub target_currency: String,
-    pub conversion_rate: f64,
+    pub conversion_rate: Decimal,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub converted_amount: Option<Decimal>,
+    pub stale: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stale_age_secs: Option<u64>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub(crate) struct CurrencyConverter {
     api_client: CurrencyApiClient,
-    currency_names: HashMap<String, String>,
     fetched_currencies: Arc<RwLock<Option<CurrencyInfo>>>,
+    rates_updated: broadcast::Sender<()>,
+    latest_rate: Arc<dyn LatestRate>,
 }
 
 impl CurrencyConverter {
-    pub(crate) fn new(api_client: CurrencyApiClient) -> anyhow::Result<Self> {
-        // load currency info
-        let currency_info = fs::read_to_string("supported_currencies.json")?;
-        let supported = serde_json::from_str::<SupportedCurrencies>(&currency_info)?;
-
+    pub(crate) fn new(
+        api_client: CurrencyApiClient,
+        latest_rate: Arc<dyn LatestRate>,
+    ) -> anyhow::Result<Self> {
+        let (rates_updated, _) = broadcast::channel(RATE_UPDATE_CHANNEL_CAPACITY);
         Ok(Self {
             api_client,
-            currency_names: supported.currency_names,
             fetched_currencies: Arc::new(RwLock::new(None)),
+            rates_updated,
+            latest_rate,
         })
     }
 
+    /// Subscribes to notifications that fire whenever the cached currency
+    /// rates are refreshed from the upstream providers, so callers (e.g. the
+    /// WebSocket live-conversion handler) can recompute and push fresh results.
+    pub(crate) fn subscribe_rate_updates(&self) -> broadcast::Receiver<()> {
+        self.rates_updated.subscribe()
+    }
+
     #[cfg(debug_assertions)]
     pub(crate) async fn print_unknown_currency_codes(&self) {
-        let current_info = self.get_base_currency_info().await.unwrap();
+        let (current_info, _) = self.get_base_currency_info().await.unwrap();
         let unknown_currencies = current_info
             .currency_rates
             .keys()
-            .filter(|code| !self.currency_names.contains_key(*code))
+            .filter(|code| iso::find(code).is_none())
             .cloned()
             .join(", ");
         println!("Unknown currencies: {}", unknown_currencies);
@@ -61,7 +80,7 @@ impl CurrencyConverter {
         &self,
         base_currency: String,
         target_currencies: Vec<String>,
-    ) -> anyhow::Result<Vec<CurrencyConvertResult>> {
+    ) -> Result<Vec<CurrencyConvertResult>, ConverterError> {
         let mut result = Vec::<CurrencyConvertResult>::with_capacity(target_currencies.len());
         for target_currency in target_currencies {
             let converted_currency = self
@@ -77,42 +96,106 @@ impl CurrencyConverter {
         &self,
         base_currency: String,
         target_currency: String,
-    ) -> anyhow::Result<CurrencyConvertResult> {
-        let current_info = self.get_base_currency_info().await?;
+    ) -> Result<CurrencyConvertResult, ConverterError> {
+        let (current_info, stale_age) = self.get_base_currency_info().await?;
 
-        // get the base and target currency info, if known
+        // resolve against the ISO 4217 currency set (plus our small crypto
+        // registry) rather than our own loosely maintained name map, so
+        // unknown/typo'd codes are rejected
+        self.validate_currency_code(&base_currency)?;
+        self.validate_currency_code(&target_currency)?;
+
+        // get the base and target currency rates, if known; both are
+        // expressed against USD, so dividing cancels the common pivot
         let source_current_rate = self.get_currency_rate(&current_info, &base_currency);
         let target_current_rate = self.get_currency_rate(&current_info, &target_currency);
-        if source_current_rate.is_none() || target_current_rate.is_none() {
-            return Err(anyhow!("Invalid target or source currency"));
+        let (source_rate, target_rate) = match (source_current_rate, target_current_rate) {
+            (Some(source), Some(target)) => (source, target),
+            (None, _) => {
+                return Err(ConverterError::RateMissing {
+                    code: base_currency,
+                })
+            }
+            (_, None) => {
+                return Err(ConverterError::RateMissing {
+                    code: target_currency,
+                })
+            }
+        };
+        if source_rate.is_zero() {
+            return Err(ConverterError::RateMissing {
+                code: base_currency,
+            });
         }
-
-        // convert rates:
-        //   1. from usd to source currency
-        //   2. from source rate to target
-        let source_rate = 1f64 / source_current_rate.unwrap();
-        let conversion_rate = source_rate * target_current_rate.unwrap();
+        let conversion_rate = target_rate / source_rate;
 
         Ok(CurrencyConvertResult {
             base_currency,
             target_currency,
             conversion_rate,
+            amount: None,
+            converted_amount: None,
+            stale: stale_age.is_some(),
+            stale_age_secs: stale_age.map(|age| age.as_secs()),
         })
     }
 
+    fn validate_currency_code(&self, currency_code: &str) -> Result<(), ConverterError> {
+        if iso::find(currency_code).is_some() || find_crypto_currency(currency_code).is_some() {
+            Ok(())
+        } else {
+            Err(ConverterError::InvalidCurrency {
+                symbol: currency_code.to_string(),
+            })
+        }
+    }
+
+    /// Converts a concrete amount of `base_currency` into each of
+    /// `target_currencies`, carrying both the source `amount` and the
+    /// resulting `converted_amount` on each result.
+    pub(crate) async fn convert_amount(
+        &self,
+        amount: Decimal,
+        base_currency: String,
+        target_currencies: Vec<String>,
+    ) -> Result<Vec<CurrencyConvertResult>, ConverterError> {
+        let mut results = self
+            .convert_currencies(base_currency, target_currencies)
+            .await?;
+        for result in &mut results {
+            result.amount = Some(amount);
+            result.converted_amount = Some(amount * result.conversion_rate);
+        }
+
+        Ok(results)
+    }
+
     pub(crate) fn get_currency_name(&self, currency_code: &String) -> String {
-        self.currency_names
-            .get(currency_code)
-            .unwrap_or(currency_code)
-            .clone()
+        if let Some(currency) = iso::find(currency_code) {
+            return currency.name.to_string();
+        }
+        if let Some(currency) = find_crypto_currency(currency_code) {
+            return currency.name.to_string();
+        }
+        currency_code.clone()
+    }
+
+    /// `Some(age)` if `info` was served from a persisted fallback snapshot
+    /// rather than a live fetch, based on when that snapshot was persisted.
+    fn stale_age_of(info: &CurrencyInfo) -> Option<Duration> {
+        info.fallback_persisted_at_unix_secs
+            .map(duration_since_unix_secs)
     }
 
-    async fn get_base_currency_info(&self) -> anyhow::Result<CurrencyInfo> {
+    /// Resolves the currently usable currency rates, alongside `Some(age)` if
+    /// they had to be served from a persisted fallback snapshot rather than a
+    /// live or in-memory-cached fetch.
+    async fn get_base_currency_info(&self) -> Result<(CurrencyInfo, Option<Duration>), ConverterError> {
         // double checked locking: check if currency info is present first
         let guard = self.fetched_currencies.read().await;
         if let Some(info) = &*guard {
             if info.timestamp.elapsed().as_secs() <= MAX_CACHE_TIME_SECS {
-                return Ok(info.clone());
+                return Ok((info.clone(), Self::stale_age_of(info)));
             }
         }
 
@@ -124,24 +207,169 @@ impl CurrencyConverter {
         let mut guard = self.fetched_currencies.write().await;
         if let Some(info) = &*guard {
             if info.timestamp.elapsed().as_secs() <= MAX_CACHE_TIME_SECS {
-                return Ok(info.clone());
+                return Ok((info.clone(), Self::stale_age_of(info)));
             }
         }
 
         // info is still not present, fetch it
-        let currency_info = self.api_client.fetch_currencies().await?;
+        let (currency_info, stale_age) = match self.api_client.fetch_currencies().await {
+            Ok(currency_info) => (currency_info, None),
+            // every live provider is down; fall back to the most recently
+            // persisted snapshot instead of failing the whole request. the
+            // fallback is cached just like a live fetch so that subsequent
+            // requests get it instantly instead of re-paying the full
+            // provider timeout chain on every single request during an outage
+            Err(err) => {
+                let latest_rate = self.latest_rate.clone();
+                let snapshot = tokio::task::spawn_blocking(move || latest_rate.latest_rate())
+                    .await
+                    .unwrap_or(None);
+                match snapshot {
+                    Some(snapshot) => {
+                        let age = snapshot.age();
+                        let currency_info = CurrencyInfo {
+                            timestamp: Instant::now(),
+                            currency_rates: snapshot.currency_rates,
+                            fallback_persisted_at_unix_secs: Some(snapshot.persisted_at_unix_secs),
+                        };
+                        (currency_info, Some(age))
+                    }
+                    None => return Err(err),
+                }
+            }
+        };
+
         *guard = Some(currency_info.clone());
-        Ok(currency_info)
+        drop(guard);
+
+        // only notify subscribers and persist a new snapshot for genuinely
+        // fresh rates, never for a fallback snapshot we just re-cached
+        if stale_age.is_none() {
+            // there may be no subscribers at all, in which case the send is a no-op
+            let _ = self.rates_updated.send(());
+
+            let latest_rate = self.latest_rate.clone();
+            let currency_rates = currency_info.currency_rates.clone();
+            let _ = tokio::task::spawn_blocking(move || latest_rate.record_rate(&currency_rates));
+        }
+
+        Ok((currency_info, stale_age))
     }
 
     fn get_currency_rate(
         &self,
         currency_info: &CurrencyInfo,
         currency_code: &String,
-    ) -> Option<f64> {
+    ) -> Option<Decimal> {
         match currency_code.as_str() {
             "USD" => ONE_USD_RATE,
             _ => currency_info.currency_rates.get(currency_code).cloned(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use super::*;
+    use crate::currency_api::{Aggregation, CurrencyApiClient, RateProvider};
+    use crate::rate_cache::{FixedRate, PersistedSnapshot};
+
+    struct StubProvider {
+        rates: HashMap<String, Decimal>,
+    }
+
+    #[async_trait::async_trait]
+    impl RateProvider for StubProvider {
+        fn name(&self) -> &'static str {
+            "Stub"
+        }
+
+        async fn fetch_rates(&self) -> anyhow::Result<HashMap<String, Decimal>> {
+            Ok(self.rates.clone())
+        }
+    }
+
+    struct FailingProvider {
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl RateProvider for FailingProvider {
+        fn name(&self) -> &'static str {
+            "Failing"
+        }
+
+        async fn fetch_rates(&self) -> anyhow::Result<HashMap<String, Decimal>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Err(anyhow::anyhow!("provider down"))
+        }
+    }
+
+    struct FixedSnapshot(PersistedSnapshot);
+
+    impl LatestRate for FixedSnapshot {
+        fn latest_rate(&self) -> Option<PersistedSnapshot> {
+            Some(self.0.clone())
+        }
+
+        fn record_rate(&self, _currency_rates: &HashMap<String, Decimal>) {}
+    }
+
+    #[tokio::test]
+    async fn zero_source_rate_is_rejected_instead_of_panicking_on_division() {
+        let rates = HashMap::from([("EUR".to_string(), Decimal::ZERO)]);
+        let api_client = CurrencyApiClient::with_providers(
+            vec![Box::new(StubProvider { rates })],
+            Aggregation::default(),
+        );
+        let converter =
+            CurrencyConverter::new(api_client, Arc::new(FixedRate)).expect("converter builds");
+
+        let result = converter
+            .convert_currency("EUR".to_string(), "USD".to_string())
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(ConverterError::RateMissing { code }) if code == "EUR"
+        ));
+    }
+
+    #[tokio::test]
+    async fn fallback_snapshot_is_cached_instead_of_refetched_every_request() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let api_client = CurrencyApiClient::with_providers(
+            vec![Box::new(FailingProvider {
+                calls: calls.clone(),
+            })],
+            Aggregation::default(),
+        );
+        let persisted_at_unix_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            - 60;
+        let snapshot = PersistedSnapshot {
+            persisted_at_unix_secs,
+            currency_rates: HashMap::from([("EUR".to_string(), Decimal::new(9, 1))]),
+        };
+        let converter = CurrencyConverter::new(api_client, Arc::new(FixedSnapshot(snapshot)))
+            .expect("converter builds");
+
+        let (_, first_stale_age) = converter
+            .get_base_currency_info()
+            .await
+            .expect("falls back to the snapshot");
+        let (_, second_stale_age) = converter
+            .get_base_currency_info()
+            .await
+            .expect("served from the in-memory cache");
+
+        assert!(first_stale_age.is_some());
+        assert!(second_stale_age.is_some());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
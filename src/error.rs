@@ -0,0 +1,40 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use thiserror::Error;
+
+/// Errors that can occur while resolving or converting currencies, mapped to
+/// a meaningful HTTP status and a structured JSON body when returned from an
+/// axum handler.
+#[derive(Error, Debug)]
+pub(crate) enum ConverterError {
+    #[error("unknown currency: {symbol}")]
+    InvalidCurrency { symbol: String },
+    #[error("currency provider {provider} is unavailable")]
+    UpstreamUnavailable { provider: String },
+    #[error("no rate available for currency: {code}")]
+    RateMissing { code: String },
+    #[error("invalid amount: {raw}")]
+    InvalidAmount { raw: String },
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl IntoResponse for ConverterError {
+    fn into_response(self) -> Response {
+        eprintln!("Unable to get currency info: {}", self);
+
+        let status = match &self {
+            ConverterError::InvalidCurrency { .. } => StatusCode::BAD_REQUEST,
+            ConverterError::UpstreamUnavailable { .. } => StatusCode::BAD_GATEWAY,
+            ConverterError::RateMissing { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            ConverterError::InvalidAmount { .. } => StatusCode::BAD_REQUEST,
+        };
+
+        (status, Json(ErrorBody { error: self.to_string() })).into_response()
+    }
+}
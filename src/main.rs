@@ -1,16 +1,27 @@
+mod crypto;
 mod currency_api;
 mod currency_convert;
+mod error;
+mod rate_cache;
 
-use crate::currency_api::CurrencyApiClient;
+use crate::crypto::find_crypto_currency;
+use crate::currency_api::{Aggregation, CurrencyApiClient};
 use crate::currency_convert::{CurrencyConvertResult, CurrencyConverter};
-use axum::extract::Path;
-use axum::http::StatusCode;
+use crate::error::ConverterError;
+use crate::rate_cache::{CachedRate, FixedRate, LatestRate, DEFAULT_SNAPSHOT_PATH};
+use axum::extract::ws::{Message, WebSocket};
+use axum::extract::{Path, WebSocketUpgrade};
 use axum::response::IntoResponse;
-use axum::{routing, Extension, Router};
+use axum::{routing, Extension, Json, Router};
 use itertools::Itertools;
+use rust_decimal::Decimal;
+use rusty_money::iso;
 use std::env;
 use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
 use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -18,10 +29,27 @@ async fn main() -> anyhow::Result<()> {
     let bind_host = env::var("BIND").expect("Missing bind host");
     let ff_api_token = env::var("FF_API_TOKEN").expect("Missing FF Api Token");
     let xe_api_token = env::var("XE_API_TOKEN").expect("Missing XE Api Token");
-    let currency_api_client = CurrencyApiClient::new(ff_api_token, xe_api_token);
+
+    // lets deployments pick how rates are merged when multiple providers
+    // report a value for the same currency; falls back to FirstAvailable
+    // for an unset or unrecognized value
+    let aggregation = env::var("AGGREGATION")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_default();
+    let currency_api_client = CurrencyApiClient::new(ff_api_token, xe_api_token, aggregation);
+
+    // deployments that don't want rates persisted to (and served from) local
+    // disk during an upstream outage can opt out via this env var
+    let latest_rate: Arc<dyn LatestRate> = if env::var("DISABLE_RATE_CACHE").is_ok() {
+        Arc::new(FixedRate)
+    } else {
+        let snapshot_path = env::var("RATE_CACHE_PATH").unwrap_or_else(|_| DEFAULT_SNAPSHOT_PATH.to_string());
+        Arc::new(CachedRate::new(snapshot_path))
+    };
 
     // build currency converter
-    let currency_converter = CurrencyConverter::new(currency_api_client)?;
+    let currency_converter = CurrencyConverter::new(currency_api_client, latest_rate)?;
 
     // list currencies that are not yet named (only when running in debug mode)
     #[cfg(debug_assertions)]
@@ -36,6 +64,14 @@ async fn main() -> anyhow::Result<()> {
             "/status/:base_currency/:target_currencies",
             routing::get(handle_currency_status_convert_request),
         )
+        .route(
+            "/live/:base_currency/:target_currencies",
+            routing::get(handle_live_convert_request),
+        )
+        .route(
+            "/convert/:amount/:base_currency/:target_currencies",
+            routing::get(handle_amount_convert_request),
+        )
         .layer(Extension(currency_converter));
 
     let address = bind_host
@@ -50,19 +86,19 @@ async fn main() -> anyhow::Result<()> {
 async fn handle_currency_status_request(
     Path(base_currency): Path<String>,
     Extension(converter): Extension<CurrencyConverter>,
-) -> impl IntoResponse {
+) -> Result<String, ConverterError> {
     let upper_base = base_currency.to_uppercase();
     let target_currencies = vec!["EUR".to_string(), "USD".to_string()];
     let converted = converter
         .convert_currencies(upper_base.clone(), target_currencies)
-        .await;
-    format_currency_response(converter, upper_base, converted)
+        .await?;
+    Ok(format_currency_response(&converter, upper_base, converted))
 }
 
 async fn handle_currency_status_convert_request(
     Path((base_currency, target_currencies)): Path<(String, String)>,
     Extension(converter): Extension<CurrencyConverter>,
-) -> impl IntoResponse {
+) -> Result<String, ConverterError> {
     let upper_base = base_currency.to_uppercase();
     let target_currencies: Vec<String> = target_currencies
         .split(',')
@@ -73,37 +109,112 @@ async fn handle_currency_status_convert_request(
         .collect();
     let converted = converter
         .convert_currencies(upper_base.clone(), target_currencies)
-        .await;
-    format_currency_response(converter, upper_base, converted)
+        .await?;
+    Ok(format_currency_response(&converter, upper_base, converted))
 }
 
-fn format_currency_response(
+async fn handle_amount_convert_request(
+    Path((amount, base_currency, target_currencies)): Path<(String, String, String)>,
+    Extension(converter): Extension<CurrencyConverter>,
+) -> Result<Json<Vec<CurrencyConvertResult>>, ConverterError> {
+    let parsed_amount = Decimal::from_str(&amount)
+        .ok()
+        .filter(|value| *value > Decimal::ZERO)
+        .ok_or(ConverterError::InvalidAmount { raw: amount })?;
+
+    let upper_base = base_currency.to_uppercase();
+    let target_currencies: Vec<String> = target_currencies
+        .split(',')
+        .map(|str| str.trim())
+        .map(|str| str.to_uppercase())
+        .unique()
+        .take(3)
+        .collect();
+
+    let converted = converter
+        .convert_amount(parsed_amount, upper_base, target_currencies)
+        .await?;
+    Ok(Json(converted))
+}
+
+async fn handle_live_convert_request(
+    Path((base_currency, target_currencies)): Path<(String, String)>,
+    ws: WebSocketUpgrade,
+    Extension(converter): Extension<CurrencyConverter>,
+) -> impl IntoResponse {
+    let upper_base = base_currency.to_uppercase();
+    let target_currencies: Vec<String> = target_currencies
+        .split(',')
+        .map(|str| str.trim())
+        .map(|str| str.to_uppercase())
+        .unique()
+        .take(3)
+        .collect();
+
+    ws.on_upgrade(move |socket| stream_live_conversions(socket, converter, upper_base, target_currencies))
+}
+
+/// Pushes a fresh [`CurrencyConvertResult`] JSON array to the socket on
+/// connect, then again every time the cached rates are refreshed, until the
+/// client disconnects or the conversion fails.
+async fn stream_live_conversions(
+    mut socket: WebSocket,
     converter: CurrencyConverter,
     base_currency: String,
-    convert_results: anyhow::Result<Vec<CurrencyConvertResult>>,
-) -> impl IntoResponse {
-    match convert_results {
-        Err(err) => {
-            eprintln!("Unable get currency info: {}", err);
-            (
-                StatusCode::OK,
-                String::from("Unable to provide info about requested currencies"),
-            )
+    target_currencies: Vec<String>,
+) {
+    let mut rate_updates = converter.subscribe_rate_updates();
+
+    loop {
+        let converted = converter
+            .convert_currencies(base_currency.clone(), target_currencies.clone())
+            .await;
+        match converted {
+            Ok(results) => match serde_json::to_string(&results) {
+                Ok(payload) => {
+                    if socket.send(Message::Text(payload)).await.is_err() {
+                        return;
+                    }
+                }
+                Err(_) => return,
+            },
+            Err(err) => {
+                eprintln!("Unable to compute live currency conversion: {}", err);
+                return;
+            }
         }
-        Ok(results) => {
-            // 1 Turkish Lira is equal to 0.03065 Euro, 0.03353 United States Dollar
-            let base_currency_name = converter.get_currency_name(&base_currency);
-            let formatted_results = results
-                .iter()
-                .map(|result| {
-                    let currency_name = converter.get_currency_name(&result.target_currency);
-                    format!("{:.15} {}", &result.conversion_rate, currency_name)
-                })
-                .join(", ");
-
-            let formatted_result =
-                format!("1 {} is equal to {}", base_currency_name, formatted_results);
-            (StatusCode::OK, formatted_result)
+
+        loop {
+            match rate_updates.recv().await {
+                Ok(_) => break,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            }
         }
     }
 }
+
+fn format_currency_response(
+    converter: &CurrencyConverter,
+    base_currency: String,
+    convert_results: Vec<CurrencyConvertResult>,
+) -> String {
+    // 1 Turkish Lira is equal to 0.03065 Euro, 0.03353 United States Dollar
+    let base_currency_name = converter.get_currency_name(&base_currency);
+    let formatted_results = convert_results
+        .iter()
+        .map(|result| {
+            let currency_name = converter.get_currency_name(&result.target_currency);
+            // round to the currency's own minor-unit exponent (e.g. 2 for
+            // USD/EUR, 0 for JPY, 8 for crypto) instead of printing 15 noisy digits
+            let exponent = iso::find(&result.target_currency)
+                .map(|currency| currency.exponent)
+                .or_else(|| find_crypto_currency(&result.target_currency).map(|currency| currency.exponent))
+                .unwrap_or(2);
+            let rounded_rate = result.conversion_rate.round_dp(exponent);
+            format!("{} {}", rounded_rate, currency_name)
+        })
+        .join(", ");
+
+    format!("1 {} is equal to {}", base_currency_name, formatted_results)
+}
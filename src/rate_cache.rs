@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// Default location of the persisted rate snapshot on disk, used unless
+/// overridden (e.g. via the `RATE_CACHE_PATH` environment variable).
+pub(crate) const DEFAULT_SNAPSHOT_PATH: &str = "currency_rates_cache.json";
+
+/// Returns how long ago `unix_secs` was, saturating at zero for clock skew.
+pub(crate) fn duration_since_unix_secs(unix_secs: u64) -> Duration {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Duration::from_secs(now.saturating_sub(unix_secs))
+}
+
+/// A snapshot of currency rates as they looked at `persisted_at_unix_secs`,
+/// used to keep answering requests while every live [`RateProvider`] is down.
+///
+/// [`RateProvider`]: crate::currency_api::RateProvider
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct PersistedSnapshot {
+    pub persisted_at_unix_secs: u64,
+    pub currency_rates: HashMap<String, Decimal>,
+}
+
+impl PersistedSnapshot {
+    pub fn age(&self) -> Duration {
+        duration_since_unix_secs(self.persisted_at_unix_secs)
+    }
+}
+
+/// Supplies the most recently known currency rates for use as a fallback
+/// when every live rate provider is unavailable.
+pub(crate) trait LatestRate: Send + Sync {
+    fn latest_rate(&self) -> Option<PersistedSnapshot>;
+
+    fn record_rate(&self, currency_rates: &HashMap<String, Decimal>);
+}
+
+/// A [`LatestRate`] that never has anything cached, for deployments that
+/// don't want the persisted-fallback behavior.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct FixedRate;
+
+impl LatestRate for FixedRate {
+    fn latest_rate(&self) -> Option<PersistedSnapshot> {
+        None
+    }
+
+    fn record_rate(&self, _currency_rates: &HashMap<String, Decimal>) {}
+}
+
+/// A [`LatestRate`] backed by a JSON snapshot file on local disk.
+#[derive(Clone, Debug)]
+pub(crate) struct CachedRate {
+    snapshot_path: PathBuf,
+}
+
+impl CachedRate {
+    pub fn new(snapshot_path: impl Into<PathBuf>) -> Self {
+        Self {
+            snapshot_path: snapshot_path.into(),
+        }
+    }
+}
+
+impl LatestRate for CachedRate {
+    fn latest_rate(&self) -> Option<PersistedSnapshot> {
+        let content = fs::read_to_string(&self.snapshot_path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn record_rate(&self, currency_rates: &HashMap<String, Decimal>) {
+        let persisted_at_unix_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let snapshot = PersistedSnapshot {
+            persisted_at_unix_secs,
+            currency_rates: currency_rates.clone(),
+        };
+
+        // best-effort: a failed write just means we fall back to a live fetch
+        // next time instead of a persisted snapshot
+        if let Ok(content) = serde_json::to_string(&snapshot) {
+            let _ = fs::write(&self.snapshot_path, content);
+        }
+    }
+}